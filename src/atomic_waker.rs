@@ -0,0 +1,53 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Waker;
+
+/// A waker slot that can be registered from a task and woken from an
+/// interrupt handler.
+///
+/// Only one waker is kept at a time, which is all a single-consumer
+/// Rx/Tx queue needs. Access is serialized by a `bool` spin guard; on
+/// the single-core Cortex-M targets this driver runs on there is no
+/// real contention, the guard only protects against an interrupt
+/// preempting `register()` mid-store.
+pub struct AtomicWaker {
+    locked: AtomicBool,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+    /// Create a new `AtomicWaker` with no registered waker.
+    pub const fn new() -> Self {
+        AtomicWaker {
+            locked: AtomicBool::new(false),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Register the waker to be notified on the next `wake()`.
+    pub fn register(&self, waker: &Waker) {
+        while self.locked.swap(true, Ordering::Acquire) {}
+        let slot = unsafe { &mut *self.waker.get() };
+        match slot {
+            Some(existing) if existing.will_wake(waker) => {},
+            _ => *slot = Some(waker.clone()),
+        }
+        self.locked.store(false, Ordering::Release);
+    }
+
+    /// Wake the registered waker, if any.
+    pub fn wake(&self) {
+        if self.locked.swap(true, Ordering::Acquire) {
+            // A `register()` is in progress; it will observe any state
+            // we would have woken for once it finishes.
+            return;
+        }
+        let waker = unsafe { &mut *self.waker.get() }.take();
+        self.locked.store(false, Ordering::Release);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! A [smoltcp](https://docs.rs/smoltcp)-style token API backed by the
+//! [`RxRing`](../rx/struct.RxRing.html) and
+//! [`TxRing`](../tx/struct.TxRing.html).
+//!
+//! This lets a TCP/IP stack drive the driver through `receive()` /
+//! `transmit()` tokens instead of the polling
+//! [`recv_next()`](../struct.Eth.html#method.recv_next) /
+//! [`send()`](../struct.Eth.html#method.send) loop. It mirrors the
+//! `embassy-net-driver` `Device` split into an `RxToken` that lends out
+//! a received frame and a `TxToken` that lends out a fresh transmit
+//! buffer.
+//!
+//! [`TxToken::consume()`](struct.TxToken.html#method.consume) allocates
+//! its scratch buffer, so the whole module is gated on the `alloc`
+//! feature (see the `pub mod device` declaration in `lib.rs`); the
+//! heap-free [`RxRing::new_static()`](../rx/struct.RxRing.html#method.new_static)
+//! path does not pull it in.
+
+use super::{Buffer, Eth, RxFrame, MTU};
+
+/// Lends out a single received frame for the duration of `consume()`.
+pub struct RxToken {
+    frame: RxFrame,
+}
+
+/// Lends out a transmit buffer, queued on the Tx ring when consumed.
+pub struct TxToken<'a> {
+    eth: &'a mut Eth,
+}
+
+impl Eth {
+    /// Obtain a receive/transmit token pair if a frame is ready.
+    ///
+    /// Returns `None` when no frame has been received, or when the Tx
+    /// ring has no free descriptor for the paired `TxToken` to use,
+    /// matching the smoltcp `Device::receive()` contract. The latter
+    /// check runs before the frame is taken off the ring, so a reply
+    /// that can't be queued yet doesn't cost the received frame too.
+    pub fn receive(&mut self) -> Option<(RxToken, TxToken)> {
+        if self.tx_full() {
+            return None;
+        }
+        // Split the borrow: the received buffer is owned by the token,
+        // the `Eth` reference only backs transmission.
+        let frame = self.recv_next().ok()?;
+        let eth = self as *mut Eth;
+        let rx = RxToken { frame };
+        let tx = TxToken { eth: unsafe { &mut *eth } };
+        Some((rx, tx))
+    }
+
+    /// Obtain a transmit token, matching smoltcp `Device::transmit()`.
+    ///
+    /// Returns `None` when the ring has no free descriptor, so a caller
+    /// gets real backpressure instead of a token whose `consume()` would
+    /// silently drop the frame.
+    pub fn transmit(&mut self) -> Option<TxToken> {
+        if self.tx_full() {
+            return None;
+        }
+        Some(TxToken { eth: self })
+    }
+}
+
+impl RxToken {
+    /// The hardware checksum-verification result for this frame.
+    pub fn checksum(&self) -> super::ChecksumStatus {
+        self.frame.checksum
+    }
+
+    /// Hand the received frame to `f` and return its result.
+    pub fn consume<R, F>(mut self, f: F) -> R
+        where F: FnOnce(&mut [u8]) -> R {
+        f(&mut self.frame.buffer)
+    }
+}
+
+impl<'a> TxToken<'a> {
+    /// Allocate a `len`-byte transmit buffer, fill it via `f`, and queue
+    /// it for transmission.
+    pub fn consume<R, F>(self, len: usize, f: F) -> R
+        where F: FnOnce(&mut [u8]) -> R {
+        let mut buffer = Buffer::new(MTU);
+        buffer.set_len(len);
+        let result = f(&mut buffer);
+        self.eth.send(buffer);
+        result
+    }
+}
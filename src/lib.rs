@@ -1,7 +1,6 @@
-#![feature(alloc, allocator_api)]
 #![no_std]
 
-extern crate cortex_m_semihosting;
+#[cfg(feature = "alloc")]
 extern crate alloc;
 extern crate volatile_register;
 
@@ -15,12 +14,29 @@ use self::phy::{Phy, PhyStatus};
 mod smi;
 mod rx;
 use self::rx::RxRing;
+pub use self::rx::{ChecksumStatus, FrameType, PacketQueue, RxError, RxFrame, RxRingEntry};
 mod tx;
 use self::tx::TxRing;
+pub use self::tx::{ChecksumInsertion, TxQueue};
 mod buffer;
 pub use self::buffer::Buffer;
 mod setup;
 pub use self::setup::setup;
+mod atomic_waker;
+use self::atomic_waker::AtomicWaker;
+#[cfg(feature = "alloc")]
+pub mod device;
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Woken by [`eth_interrupt_handler()`](fn.eth_interrupt_handler.html)
+/// when the Rx DMA engine signals a received frame.
+static RX_WAKER: AtomicWaker = AtomicWaker::new();
+/// Woken by [`eth_interrupt_handler()`](fn.eth_interrupt_handler.html)
+/// when the Tx DMA engine frees a descriptor.
+static TX_WAKER: AtomicWaker = AtomicWaker::new();
 
 #[cfg(feature = "smoltcp_phy")]
 extern crate smoltcp;
@@ -34,6 +50,63 @@ pub const ALIGNMENT: usize = 0b1000;
 const PHY_ADDR: u8 = 0;
 const MTU: usize = 1518;
 
+/// PHY Special Status Register (LAN8742), decoded by
+/// [`LinkMode::from_ssr()`](struct.LinkMode.html#method.from_ssr).
+const PHY_REG_SSR: u8 = 0x1F;
+/// Auto-negotiation done.
+const PHY_REG_SSR_AUTONEG_DONE: u16 = 1 << 12;
+/// Speed/duplex indication field.
+const PHY_REG_SSR_SPEED: u16 = 0b111 << 2;
+const PHY_REG_SSR_10BASE_HD: u16 = 0b001 << 2;
+const PHY_REG_SSR_10BASE_FD: u16 = 0b101 << 2;
+const PHY_REG_SSR_100BASE_HD: u16 = 0b010 << 2;
+const PHY_REG_SSR_100BASE_FD: u16 = 0b110 << 2;
+/// PHY interrupt mask register (LAN8742 reg 30; enable the link-change
+/// source). Reg 27 (`0x1B`) is reserved.
+const PHY_REG_IMR: u8 = 0x1E;
+const PHY_REG_IMR_LINK_DOWN: u16 = 1 << 4;
+/// Auto-negotiation complete, i.e. the link came (back) up.
+const PHY_REG_IMR_AUTONEG_DONE: u16 = 1 << 6;
+
+/// Negotiated link speed.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Speed {
+    Mbps10,
+    Mbps100,
+}
+
+/// Negotiated duplex mode.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Duplex {
+    Half,
+    Full,
+}
+
+/// The speed/duplex the PHY auto-negotiated with its peer.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct LinkMode {
+    pub speed: Speed,
+    pub duplex: Duplex,
+}
+
+impl LinkMode {
+    /// Decode a PHY Special Status Register value, or `None` while
+    /// auto-negotiation is still in progress.
+    pub fn from_ssr(ssr: u16) -> Option<LinkMode> {
+        if ssr & PHY_REG_SSR_AUTONEG_DONE == 0 {
+            return None;
+        }
+        let (speed, duplex) = match ssr & PHY_REG_SSR_SPEED {
+            PHY_REG_SSR_10BASE_HD => (Speed::Mbps10, Duplex::Half),
+            PHY_REG_SSR_10BASE_FD => (Speed::Mbps10, Duplex::Full),
+            PHY_REG_SSR_100BASE_HD => (Speed::Mbps100, Duplex::Half),
+            PHY_REG_SSR_100BASE_FD => (Speed::Mbps100, Duplex::Full),
+            _ => return None,
+        };
+        Some(LinkMode { speed, duplex })
+    }
+}
+
 #[allow(dead_code)]
 mod consts {
     /* For HCLK 60-100 MHz */
@@ -68,19 +141,43 @@ impl Eth {
     /// Other than that, initializes and starts the Ethernet hardware
     /// so that you can [`send()`](#method.send) and
     /// [`recv_next()`](#method.recv_next).
+    #[cfg(feature = "alloc")]
     pub fn new(eth_mac: ETHERNET_MAC, eth_dma: ETHERNET_DMA, rx_ring_len: usize) -> Self {
-        let mut eth = Eth {
+        let eth = Eth {
             eth_mac,
             eth_dma,
             rx: RxRing::new(MTU),
             tx: TxRing::new(),
         };
-        eth.init();
-        eth.tx.start(&eth.eth_dma);
+        eth.bringup(rx_ring_len)
+    }
+
+    /// Initialize and start the engines over a heap-free
+    /// [`RxRing`](rx/struct.RxRing.html) built from a
+    /// [`PacketQueue`](rx/struct.PacketQueue.html) and a
+    /// [`TxRing`](tx/struct.TxRing.html) built from a
+    /// [`TxQueue`](tx/struct.TxQueue.html).
+    ///
+    /// As with [`new()`](#method.new) you must call
+    /// [`setup()`](fn.setup.html) first.
+    #[cfg(not(feature = "alloc"))]
+    pub fn new_static(eth_mac: ETHERNET_MAC, eth_dma: ETHERNET_DMA, rx: RxRing, tx: TxRing, rx_ring_len: usize) -> Self {
+        let eth = Eth {
+            eth_mac,
+            eth_dma,
+            rx,
+            tx,
+        };
+        eth.bringup(rx_ring_len)
+    }
+
+    fn bringup(mut self, rx_ring_len: usize) -> Self {
+        self.init();
+        self.tx.start(&self.eth_dma);
         if rx_ring_len > 0 {
-            eth.start_rx(rx_ring_len);
+            self.start_rx(rx_ring_len);
         }
-        eth
+        self
     }
 
     fn init(&mut self) -> &Self {
@@ -98,6 +195,11 @@ impl Eth {
         self.eth_mac.maccr.modify(|_, w| {
             // CRC stripping for Type frames
             w.cstf().set_bit()
+                // IPv4/TCP/UDP checksum offload: verify RX checksums in
+                // hardware and surface the result via
+                // [`RxFrame::checksum`](rx/struct.RxFrame.html). TX
+                // insertion is selected per-descriptor in `tx.rs`.
+                .ipco().set_bit()
                 // Fast Ethernet speed
                 .fes().set_bit()
                 // Duplex mode
@@ -154,9 +256,30 @@ impl Eth {
                 .usp().set_bit()
         });
 
+        // The `fes`/`dm` bits above are just fallback defaults.
+        // Auto-negotiation was (re)started just above, so give it a
+        // bounded chance to settle before reading the result — otherwise
+        // `reconfigure_link()` always sees it in progress and the MAC
+        // stays pinned to those 100 Mbit full-duplex defaults. If the
+        // link isn't up yet the wait times out and the PHY link-change
+        // interrupt (see `enable_phy_interrupt()`) reconfigures later.
+        self.wait_for_autoneg();
+        self.reconfigure_link();
+
         self
     }
 
+    /// Spin until the PHY reports auto-negotiation complete, bounded so
+    /// a missing link can't hang bring-up forever.
+    fn wait_for_autoneg(&self) {
+        let phy = self.get_phy();
+        for _ in 0..1_000_000 {
+            if phy.read(PHY_REG_SSR) & PHY_REG_SSR_AUTONEG_DONE != 0 {
+                break;
+            }
+        }
+    }
+
     /// reset DMA bus mode register
     fn reset_dma_and_wait(&self) {
         self.eth_dma.dmabmr.modify(|_, w| w.sr().set_bit());
@@ -201,6 +324,37 @@ impl Eth {
         self.get_phy().status()
     }
 
+    /// Read the PHY's negotiated [`LinkMode`](struct.LinkMode.html), or
+    /// `None` while auto-negotiation is still running.
+    pub fn link_mode(&self) -> Option<LinkMode> {
+        LinkMode::from_ssr(self.get_phy().read(PHY_REG_SSR))
+    }
+
+    /// Re-read the PHY auto-negotiation result and program the MAC's
+    /// speed (`fes`) and duplex (`dm`) bits to match.
+    ///
+    /// Call this once the link comes up, and again from your PHY
+    /// link-change interrupt handler (see
+    /// [`enable_phy_interrupt()`](#method.enable_phy_interrupt)) so the
+    /// MAC tracks link transitions instead of staying pinned to the
+    /// 100 Mbit full-duplex defaults set by [`init()`](#method.init).
+    pub fn reconfigure_link(&self) -> Option<LinkMode> {
+        let mode = self.link_mode()?;
+        self.eth_mac.maccr.modify(|_, w|
+            w.fes().bit(mode.speed == Speed::Mbps100)
+                .dm().bit(mode.duplex == Duplex::Full)
+        );
+        Some(mode)
+    }
+
+    /// Unmask the PHY's link-change interrupt source so a link
+    /// transition asserts the PHY interrupt pin. Wire that pin to an
+    /// EXTI line and call [`reconfigure_link()`](#method.reconfigure_link)
+    /// from its handler.
+    pub fn enable_phy_interrupt(&self) {
+        self.get_phy().write(PHY_REG_IMR, PHY_REG_IMR_LINK_DOWN | PHY_REG_IMR_AUTONEG_DONE);
+    }
+
     /// Start Rx DMA engine with a certain `ring_length`
     pub fn start_rx(&mut self, ring_length: usize) -> &mut Self {
         self.rx.start(ring_length, &self.eth_dma);
@@ -216,9 +370,10 @@ impl Eth {
         self.rx.running_state(&self.eth_dma).is_running()
     }
 
-    /// Receive the next packet (if any is ready), or return `None`
-    /// immediately.
-    pub fn recv_next(&mut self) -> Option<Buffer> {
+    /// Receive the next packet, or return an [`RxError`](enum.RxError.html)
+    /// immediately ([`WouldBlock`](enum.RxError.html#variant.WouldBlock)
+    /// if none is ready).
+    pub fn recv_next(&mut self) -> Result<RxFrame, RxError> {
         self.rx.recv_next(&self.eth_dma)
     }
 
@@ -237,6 +392,104 @@ impl Eth {
     pub fn queue_len(&self) -> usize {
         self.tx.queue_len()
     }
+
+    /// Would [`send()`](#method.send) have to drop a buffer right now
+    /// because the ring has no free descriptor?
+    pub fn tx_full(&self) -> bool {
+        self.tx.is_full()
+    }
+
+    /// Choose which L3/L4 checksums the hardware inserts into outgoing
+    /// frames. Defaults to [`ChecksumInsertion::Disabled`](enum.ChecksumInsertion.html#variant.Disabled)
+    /// so existing callers keep computing checksums in software; opt in
+    /// to match the RX verification enabled by `maccr.ipco` in
+    /// [`init()`](#method.init) so a smoltcp stack can drop both
+    /// software checksums.
+    pub fn set_tx_checksum_insertion(&mut self, mode: ChecksumInsertion) {
+        self.tx.set_checksum_insertion(mode);
+    }
+
+    /// Receive the next packet, waiting asynchronously until one is
+    /// ready.
+    ///
+    /// The returned future registers the polling task with `RX_WAKER`
+    /// and yields `Poll::Pending` while [`recv_next()`](#method.recv_next)
+    /// has nothing to hand out. It is woken from
+    /// [`eth_interrupt_handler()`](fn.eth_interrupt_handler.html), so
+    /// [`enable_interrupt()`](#method.enable_interrupt) must be in
+    /// effect.
+    pub fn recv_next_async(&mut self) -> RecvNextFuture {
+        RecvNextFuture { eth: self }
+    }
+
+    /// Send a packet, waiting asynchronously until the Tx ring has a
+    /// free descriptor for it. The future completes once the packet is
+    /// queued, not once it has been transmitted.
+    ///
+    /// Like [`recv_next_async()`](#method.recv_next_async), the future
+    /// parks on `TX_WAKER` until the Tx DMA engine frees a descriptor.
+    pub fn send_async(&mut self, buffer: Buffer) -> SendFuture {
+        SendFuture { eth: self, buffer: Some(buffer) }
+    }
+}
+
+/// Future returned by [`Eth::recv_next_async()`](struct.Eth.html#method.recv_next_async).
+pub struct RecvNextFuture<'a> {
+    eth: &'a mut Eth,
+}
+
+impl<'a> Future for RecvNextFuture<'a> {
+    type Output = RxFrame;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<RxFrame> {
+        let eth = &mut self.get_mut().eth;
+        // Register before the final check so a frame that arrives
+        // between the check and parking still wakes us.
+        RX_WAKER.register(cx.waker());
+        // Drain already-queued descriptors rather than parking on the
+        // first error: a truncated/errored frame has been re-armed and
+        // skipped by `recv_next()`, but a good frame may already sit
+        // behind it with no further interrupt coming to wake us. Only
+        // `WouldBlock` means the ring is genuinely empty.
+        loop {
+            match eth.recv_next() {
+                Ok(frame) => return Poll::Ready(frame),
+                Err(RxError::WouldBlock) => return Poll::Pending,
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+/// Future returned by [`Eth::send_async()`](struct.Eth.html#method.send_async).
+pub struct SendFuture<'a> {
+    eth: &'a mut Eth,
+    buffer: Option<Buffer>,
+}
+
+impl<'a> Future for SendFuture<'a> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        TX_WAKER.register(cx.waker());
+        // Back off until the next descriptor is actually free, rather
+        // than just inferring "probably free" from the DMA engine being
+        // idle: `Eth::send()` silently drops the buffer if the
+        // descriptor it lands on is still owned by the DMA engine, so
+        // this is the same check `TxRingEntry::send()` makes before
+        // committing.
+        if this.eth.tx_full() {
+            return Poll::Pending;
+        }
+        match this.buffer.take() {
+            Some(buffer) => {
+                this.eth.send(buffer);
+                Poll::Ready(())
+            },
+            None => Poll::Ready(()),
+        }
+    }
 }
 
 /// Call in interrupt handler to clear interrupt reason, when
@@ -255,4 +508,9 @@ pub fn eth_interrupt_handler(eth_dma: &ETHERNET_DMA) {
         .rs().set_bit()
         .ts().set_bit()
     );
+
+    // Wake any task parked on `recv_next_async()`/`send_async()`. Done
+    // after clearing `dmasr` so the woken poll observes a fresh state.
+    RX_WAKER.wake();
+    TX_WAKER.wake();
 }
@@ -1,10 +1,10 @@
 use core::mem;
 use core::default::Default;
-use core::fmt::Write;
-use cortex_m_semihosting::hio;
+use core::sync::atomic::{fence, Ordering};
+#[cfg(feature = "alloc")]
 use alloc::Vec;
-use alloc::allocator::{Alloc, Layout};
-use alloc::heap::Heap;
+#[cfg(feature = "alloc")]
+use alloc::alloc::{alloc, dealloc, Layout};
 use board::ETHERNET_DMA;
 use volatile_register::RW;
 
@@ -19,6 +19,12 @@ const RXDESC_0_FS: u32 = 1 << 9;
 const RXDESC_0_LS: u32 = 1 << 8;
 /// Error summary
 const RXDESC_0_ES: u32 = 1 << 15;
+/// Frame type: set for an Ethernet-type frame, clear for IEEE 802.3
+const RXDESC_0_FT: u32 = 1 << 5;
+/// IPv4 header checksum error (IPHCE)
+const RXDESC_0_IPHCE: u32 = 1 << 7;
+/// IP payload checksum error (PCE)
+const RXDESC_0_PCE: u32 = 1 << 0;
 /// Frame length
 const RXDESC_0_FL_MASK: u32 = 0x3FFF;
 const RXDESC_0_FL_SHIFT: usize = 16;
@@ -35,41 +41,60 @@ struct RxDescriptor {
     rdesc: &'static mut [RW<u32>; 4],
 }
 
+#[cfg(feature = "alloc")]
 impl Default for RxDescriptor {
     fn default() -> Self {
         let mut this = Self::new();
-        this.write(0, 0);
-        this.write(1, RXDESC_1_RCH);
-        this.write(2, 0);
-        this.write(3, 0);
+        this.init();
         this
     }
 }
 
+#[cfg(feature = "alloc")]
 impl Drop for RxDescriptor {
     fn drop(&mut self) {
         unsafe {
-            Heap.dealloc(self.rdesc.as_mut_ptr() as *mut u8, Self::memory_layout())
+            dealloc(self.rdesc.as_mut_ptr() as *mut u8, Self::memory_layout())
         }
     }
 }
 
 impl RxDescriptor {
+    #[cfg(feature = "alloc")]
     fn memory_layout() -> Layout {
         Layout::from_size_align(4 * 4, super::ALIGNMENT)
             .unwrap()
     }
 
+    #[cfg(feature = "alloc")]
     fn new() -> Self {
         let mem = unsafe {
-            Heap.alloc(Self::memory_layout())
-        }.expect("alloc with memory_layout") as *mut [u32; 4];
+            alloc(Self::memory_layout())
+        } as *mut [u32; 4];
+        assert!(!mem.is_null(), "alloc with memory_layout");
 
         RxDescriptor {
             rdesc: unsafe { &mut *(mem as *mut [RW<u32>; 4]) },
         }
     }
 
+    /// Wrap caller-provided `&'static` descriptor words, for the
+    /// heap-free [`RxRing::new_static()`](struct.RxRing.html#method.new_static)
+    /// path. No allocation happens; the storage outlives the driver.
+    fn from_static(rdesc: &'static mut [RW<u32>; 4]) -> Self {
+        let mut this = RxDescriptor { rdesc };
+        this.init();
+        this
+    }
+
+    /// Reset the descriptor words to the chained-mode default.
+    fn init(&mut self) {
+        self.write(0, 0);
+        self.write(1, RXDESC_1_RCH);
+        self.write(2, 0);
+        self.write(3, 0);
+    }
+
     fn as_raw_ptr(&self) -> *const u8 {
         self.rdesc.as_ptr() as *const u8
     }
@@ -94,6 +119,11 @@ impl RxDescriptor {
     }
 
     pub fn set_owned(&mut self) {
+        // Make sure the buffer-address and length writes above are
+        // committed before the DMA engine can observe the OWN bit and
+        // start writing into the buffer. Correct only with the D-cache
+        // disabled or this descriptor region marked non-cacheable.
+        fence(Ordering::Release);
         self.modify(0, |w| w | RXDESC_0_OWN);
     }
 
@@ -111,6 +141,24 @@ impl RxDescriptor {
         (self.read(0) & RXDESC_0_LS) == RXDESC_0_LS
     }
 
+    /// Ethernet-type vs IEEE 802.3 length frame, from RDES0 FT.
+    pub fn frame_type(&self) -> FrameType {
+        if (self.read(0) & RXDESC_0_FT) == RXDESC_0_FT {
+            FrameType::EthernetType
+        } else {
+            FrameType::Length8023
+        }
+    }
+
+    /// Hardware L3/L4 checksum verification result, from RDES0 IPHCE/PCE.
+    pub fn checksum_status(&self) -> ChecksumStatus {
+        let status = self.read(0);
+        ChecksumStatus {
+            ip_header_error: (status & RXDESC_0_IPHCE) == RXDESC_0_IPHCE,
+            payload_error: (status & RXDESC_0_PCE) == RXDESC_0_PCE,
+        }
+    }
+
     pub fn set_buffer1(&mut self, buffer: *const u8, len: usize) {
         self.write(2, buffer as u32);
         self.modify(1, |w| {
@@ -129,21 +177,55 @@ impl RxDescriptor {
     }
 }
 
-struct RxRingEntry {
+/// One descriptor plus its packet buffer. Made `pub` so callers can
+/// hand a `&'static mut [RxRingEntry]` to
+/// [`RxRing::new_static()`](struct.RxRing.html#method.new_static); build
+/// them with a [`PacketQueue`](struct.PacketQueue.html).
+pub struct RxRingEntry {
     desc: RxDescriptor,
     buffer: Buffer,
+    /// Heap-free path only: address of the entry's other static buffer,
+    /// ping-ponged into `buffer` on every received frame. Keeps the
+    /// address just handed out in an `RxFrame` from also being the live
+    /// DMA target the next `set_owned()` re-arms; see `take_received()`.
+    #[cfg(not(feature = "alloc"))]
+    spare_ptr: *mut u8,
 }
 
 impl RxRingEntry {
+    #[cfg(feature = "alloc")]
     fn new(capacity: usize) -> Self {
-        let mut desc = RxDescriptor::default();
+        let desc = RxDescriptor::default();
         let buffer = Buffer::new(capacity);
-        desc.set_buffer1(buffer.as_ptr(), buffer.capacity());
-        desc.set_owned();
-        RxRingEntry {
-            desc: desc,
-            buffer,
-        }
+        let mut this = RxRingEntry { desc, buffer };
+        this.init();
+        this
+    }
+
+    /// Build an entry from caller-provided `&'static` descriptor words
+    /// and a pair of same-sized packet buffers, allocating nothing.
+    /// `buffer` is wired up as the initial DMA target; `spare` is held
+    /// in reserve and rotated in by `take_received()` so a received
+    /// frame is never handed out pointing at the same memory the DMA
+    /// engine is about to write into again.
+    #[cfg(not(feature = "alloc"))]
+    pub fn new_static(
+        rdesc: &'static mut [RW<u32>; 4],
+        buffer: &'static mut [u8],
+        spare: &'static mut [u8],
+    ) -> Self {
+        let spare_ptr = spare.as_mut_ptr();
+        let desc = RxDescriptor::from_static(rdesc);
+        let buffer = Buffer::from_static(buffer);
+        let mut this = RxRingEntry { desc, buffer, spare_ptr };
+        this.init();
+        this
+    }
+
+    /// Point the descriptor at our buffer and hand it to the DMA engine.
+    fn init(&mut self) {
+        self.desc.set_buffer1(self.buffer.as_ptr(), self.buffer.capacity());
+        self.desc.set_owned();
     }
 
     // Used to chain all buffers in the ring on start
@@ -161,49 +243,119 @@ impl RxRingEntry {
         }
     }
 
-    fn take_received(&mut self) -> Option<Buffer> {
+    fn take_received(&mut self) -> Result<RxFrame, RxError> {
         match self.desc.is_owned() {
-            true => None,
+            true => Err(RxError::WouldBlock),
             false if self.desc.has_error() => {
-                let mut stderr = hio::hstderr().unwrap();
-                writeln!(stderr, "Ethernet error: skipping error frame").unwrap();
                 self.desc.set_owned();
-                None
+                Err(RxError::DmaError)
             },
             false if self.desc.is_first() && self.desc.is_last() => {
-                // Switch old with new
+                // The OWN bit has been observed clear; fence so the
+                // frame length and buffer contents aren't read
+                // speculatively ahead of that ownership check. Relies on
+                // the descriptor/buffer region being non-cacheable.
+                fence(Ordering::Acquire);
+                // Switch old with new. The heap path hands out a fresh
+                // allocation. The heap-free path has no allocator, so it
+                // ping-pongs between the entry's two static buffers
+                // instead: the just-received buffer is handed out as
+                // `pkt_buffer` and the entry's other, untouched static
+                // buffer becomes the new DMA target, so the address just
+                // given to the caller is never also re-armed for the
+                // DMA engine to write into. (The two buffers do swap
+                // back and forth, so a frame must still be consumed
+                // before the ring wraps all the way back to this entry
+                // again, and the region must be non-cacheable.)
+                #[cfg(feature = "alloc")]
                 let new_buffer = Buffer::new(self.buffer.capacity());
+                #[cfg(not(feature = "alloc"))]
+                let new_buffer = {
+                    let ptr = self.spare_ptr;
+                    let cap = self.buffer.capacity();
+                    Buffer::from_static(unsafe { core::slice::from_raw_parts_mut(ptr, cap) })
+                };
                 let mut pkt_buffer = mem::replace(&mut self.buffer, new_buffer);
                 // Truncate received pkt to reported length
                 let frame_length = ((self.desc.read(0) >> RXDESC_0_FL_SHIFT) & RXDESC_0_FL_MASK) as usize;
                 pkt_buffer.set_len(frame_length);
-                // TODO: obtain ethernet frame type (RDESC_1_FT)
+                // Decode the descriptor status before re-arming it.
+                let frame_type = self.desc.frame_type();
+                let checksum = self.desc.checksum_status();
 
+                #[cfg(not(feature = "alloc"))]
+                {
+                    self.spare_ptr = pkt_buffer.as_ptr() as *mut u8;
+                }
                 self.desc.set_buffer1(self.buffer.as_ptr(), self.buffer.capacity());
                 self.desc.set_owned();
 
-                Some(pkt_buffer)
+                Ok(RxFrame { buffer: pkt_buffer, frame_type, checksum })
             },
+            // First/last segment bits don't mark a single complete
+            // frame: the buffer is too small and the frame spans several
+            // descriptors, which this driver doesn't reassemble.
             false => {
-                let mut stderr = hio::hstderr().unwrap();
-                writeln!(stderr, "Ethernet error: skipping truncated frame bufs (FS={:?} LS={:?})",
-                         self.desc.is_first(), self.desc.is_last()).unwrap();
                 self.desc.set_owned();
-                None
+                Err(RxError::Truncated)
             },
         }
     }
 }
 
+/// Whether the received frame used an Ethernet type or an 802.3 length
+/// field (RDES0 FT bit).
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FrameType {
+    Length8023,
+    EthernetType,
+}
+
+/// Result of the MAC's hardware IPv4/TCP/UDP checksum verification for a
+/// received frame. Both flags clear means the checksums passed (or the
+/// frame carried none); a smoltcp stack can then skip software checks.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ChecksumStatus {
+    pub ip_header_error: bool,
+    pub payload_error: bool,
+}
+
+/// A received frame together with the descriptor status the hardware
+/// reported for it.
+pub struct RxFrame {
+    pub buffer: Buffer,
+    pub frame_type: FrameType,
+    pub checksum: ChecksumStatus,
+}
+
+/// Reason [`recv_next()`](struct.RxRing.html#method.recv_next) did not
+/// yield a frame.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RxError {
+    /// The received frame spanned several descriptors (the buffer is
+    /// smaller than the frame); its fragments were dropped.
+    Truncated,
+    /// The DMA engine flagged an error in the descriptor's error summary.
+    DmaError,
+    /// No frame is ready yet; the descriptor is still owned by the DMA
+    /// engine. Retry later.
+    WouldBlock,
+}
+
 /// Rx DMA state
 pub struct RxRing {
+    #[cfg(feature = "alloc")]
     buffer_size: usize,
+    #[cfg(feature = "alloc")]
     buffers: Vec<RxRingEntry>,
+    #[cfg(not(feature = "alloc"))]
+    buffers: &'static mut [RxRingEntry],
     next_entry: usize,
 }
 
 impl RxRing {
     /// Allocate
+    #[cfg(feature = "alloc")]
     pub fn new(buffer_size: usize) -> Self {
         RxRing {
             buffer_size,
@@ -212,32 +364,51 @@ impl RxRing {
         }
     }
 
+    /// Build a ring over caller-provided `&'static mut` entries,
+    /// allocating nothing.
+    ///
+    /// Obtain the slice from a [`PacketQueue`](struct.PacketQueue.html)
+    /// so the descriptors and packet buffers live in static storage the
+    /// DMA engine can reach for the whole program lifetime.
+    #[cfg(not(feature = "alloc"))]
+    pub fn new_static(buffers: &'static mut [RxRingEntry]) -> Self {
+        RxRing {
+            buffers,
+            next_entry: 0,
+        }
+    }
+
+    /// Chain every entry's second-buffer pointer to its successor, with
+    /// the last entry closing the ring (RER).
+    fn chain(entries: &mut [RxRingEntry]) {
+        let len = entries.len();
+        for i in 0..len {
+            if i + 1 < len {
+                let (head, tail) = entries.split_at_mut(i + 1);
+                let next = tail[0].desc.as_raw_ptr();
+                head[i].desc.set_buffer2(next);
+            } else {
+                entries[i].set_next_buffer(None);
+            }
+        }
+    }
+
     /// Setup the DMA engine (**required**)
     pub fn start(&mut self, ring_length: usize, eth_dma: &ETHERNET_DMA) {
-        let mut buffers = mem::replace(&mut self.buffers, Vec::with_capacity(ring_length));
-        // Grow ring if necessary
-        let additional = ring_length.saturating_sub(buffers.len());
-        if additional > 0 {
-            self.buffers.reserve(additional);
-            while buffers.len() < ring_length {
+        // Grow the ring if necessary. With static storage the length is
+        // fixed at construction, so `ring_length` is ignored.
+        #[cfg(feature = "alloc")]
+        {
+            self.buffers.reserve(ring_length.saturating_sub(self.buffers.len()));
+            while self.buffers.len() < ring_length {
                 let buffer = RxRingEntry::new(self.buffer_size);
-                buffers.push(buffer);
+                self.buffers.push(buffer);
             }
         }
+        #[cfg(not(feature = "alloc"))]
+        let _ = ring_length;
 
-        // Setup ring from `buffers` back into `self.buffers`
-        let mut previous: Option<RxRingEntry> = None;
-        for buffer in buffers.into_iter() {
-            previous.take().map(|mut previous| {
-                previous.set_next_buffer(Some(&buffer));
-                self.buffers.push(previous);
-            });
-            previous = Some(buffer);
-        }
-        previous.map(|mut previous| {
-            previous.set_next_buffer(None);
-            self.buffers.push(previous);
-        });
+        Self::chain(&mut self.buffers);
 
         self.next_entry = 0;
         let ring_ptr = self.buffers[0].desc.as_raw_ptr();
@@ -253,6 +424,11 @@ impl RxRing {
     /// Demand that the DMA engine polls the current `RxDescriptor`
     /// (when in `RunningState::Stopped`.)
     pub fn demand_poll(&self, eth_dma: &ETHERNET_DMA) {
+        // Ensure the OWN-bit stores that released descriptors (normal
+        // memory) are globally visible before the demand-poll write to
+        // `dmarpdr` (device memory), which otherwise may be reordered
+        // ahead and leave the engine polling a still-owned descriptor.
+        fence(Ordering::Release);
         eth_dma.dmarpdr.write(|w| unsafe { w.rpd().bits(1) });
     }
 
@@ -275,19 +451,23 @@ impl RxRing {
         }
     }
 
-    /// Receive the next packet (if any is ready), or return `None`
+    /// Receive the next packet, or return an [`RxError`](enum.RxError.html)
     /// immediately.
-    pub fn recv_next(&mut self, eth_dma: &ETHERNET_DMA) -> Option<Buffer> {
-        let result = self.buffers[self.next_entry]
-            .take_received()
-            .map(|pkt| {
-                self.next_entry += 1;
-                if self.next_entry >= self.buffers.len() {
-                    self.next_entry = 0;
-                }
-
-                pkt
-            });
+    ///
+    /// [`RxError::WouldBlock`](enum.RxError.html#variant.WouldBlock)
+    /// means no frame is ready yet. `Truncated`/`DmaError` mean the
+    /// current entry held an unusable frame, which has been re-armed and
+    /// skipped over.
+    pub fn recv_next(&mut self, eth_dma: &ETHERNET_DMA) -> Result<RxFrame, RxError> {
+        let result = self.buffers[self.next_entry].take_received();
+        // Advance past the entry unless it's still owned by the DMA
+        // engine; errored entries have already been re-armed above.
+        if ! matches!(result, Err(RxError::WouldBlock)) {
+            self.next_entry += 1;
+            if self.next_entry >= self.buffers.len() {
+                self.next_entry = 0;
+            }
+        }
 
         if ! self.running_state(eth_dma).is_running() {
             self.demand_poll(eth_dma);
@@ -297,6 +477,66 @@ impl RxRing {
     }
 }
 
+/// One DMA descriptor's worth of storage, aligned so the engine never
+/// drops the low address bits (see [`ALIGNMENT`](../constant.ALIGNMENT.html)).
+#[repr(C, align(8))]
+#[derive(Clone, Copy)]
+pub struct RxDescriptorBlock([u32; 4]);
+
+/// Backing storage for a single packet, aligned like the descriptors.
+#[repr(C, align(8))]
+#[derive(Clone, Copy)]
+pub struct PacketBuffer([u8; super::MTU]);
+
+/// Const-constructible, heap-free descriptor and buffer storage for a
+/// ring of `N` entries, modeled on the embassy `PacketQueue`.
+///
+/// Place one in a `static mut` and hand the `&'static mut self` to
+/// [`rx_ring()`](#method.rx_ring); the returned [`RxRing`](struct.RxRing.html)
+/// chains the entries in place without allocating.
+#[repr(C, align(8))]
+pub struct PacketQueue<const N: usize> {
+    descriptors: [RxDescriptorBlock; N],
+    // Two buffers per entry: one is the live DMA target, the other is
+    // the spare `RxRingEntry::take_received()` rotates in so a received
+    // frame is never handed out aliasing the next DMA write.
+    buffers: [[PacketBuffer; 2]; N],
+    entries: [mem::MaybeUninit<RxRingEntry>; N],
+}
+
+impl<const N: usize> PacketQueue<N> {
+    /// Create zeroed storage. The entries are filled in by
+    /// [`rx_ring()`](#method.rx_ring).
+    pub const fn new() -> Self {
+        PacketQueue {
+            descriptors: [RxDescriptorBlock([0; 4]); N],
+            buffers: [[PacketBuffer([0; super::MTU]); 2]; N],
+            // SAFETY: an array of `MaybeUninit` needs no initialization.
+            entries: unsafe { mem::MaybeUninit::uninit().assume_init() },
+        }
+    }
+
+    /// Build an [`RxRing`](struct.RxRing.html) over this storage.
+    #[cfg(not(feature = "alloc"))]
+    pub fn rx_ring(&'static mut self) -> RxRing {
+        let descriptors = self.descriptors.as_mut_ptr();
+        let buffers = self.buffers.as_mut_ptr();
+        for i in 0..N {
+            // SAFETY: each index is touched once, so the `&'static mut`
+            // references handed to the entries are disjoint and live as
+            // long as `self`.
+            let rdesc = unsafe { &mut *(descriptors.add(i) as *mut [RW<u32>; 4]) };
+            let buffer = unsafe { &mut (*buffers.add(i))[0].0[..] };
+            let spare = unsafe { &mut (*buffers.add(i))[1].0[..] };
+            self.entries[i] = mem::MaybeUninit::new(RxRingEntry::new_static(rdesc, buffer, spare));
+        }
+        let entries = unsafe {
+            &mut *(self.entries.as_mut_ptr() as *mut [RxRingEntry; N])
+        };
+        RxRing::new_static(&mut entries[..])
+    }
+}
+
 /// Running state of the `RxRing`
 #[derive(PartialEq, Eq, Debug)]
 pub enum RunningState {
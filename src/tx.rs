@@ -0,0 +1,470 @@
+use core::mem;
+use core::default::Default;
+use core::sync::atomic::{fence, Ordering};
+#[cfg(feature = "alloc")]
+use alloc::Vec;
+#[cfg(feature = "alloc")]
+use alloc::alloc::{alloc, dealloc, Layout};
+use board::ETHERNET_DMA;
+use volatile_register::RW;
+
+use super::buffer::Buffer;
+
+
+/// Owned by DMA engine
+const TXDESC_0_OWN: u32 = 1 << 31;
+/// Interrupt on completion
+const TXDESC_0_IC: u32 = 1 << 30;
+/// Last segment
+const TXDESC_0_LS: u32 = 1 << 29;
+/// First segment
+const TXDESC_0_FS: u32 = 1 << 28;
+/// Checksum insertion control (CIC)
+const TXDESC_0_CIC_SHIFT: usize = 22;
+const TXDESC_0_CIC_MASK: u32 = 0b11 << TXDESC_0_CIC_SHIFT;
+/// Transmit end of ring
+const TXDESC_0_TER: u32 = 1 << 21;
+/// Second address chained
+const TXDESC_0_TCH: u32 = 1 << 20;
+/// Error summary
+const TXDESC_0_ES: u32 = 1 << 15;
+
+const TXDESC_1_TBS_SHIFT: usize = 0;
+const TXDESC_1_TBS_MASK: u32 = 0x0fff << TXDESC_1_TBS_SHIFT;
+
+/// Hardware checksum the MAC inserts into an outgoing frame, written to
+/// the descriptor's TDES0 CIC field. Mirrors the `Checksum` capabilities
+/// a smoltcp stack can then stop computing in software.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ChecksumInsertion {
+    /// Leave every checksum to software (CIC = 0b00).
+    Disabled,
+    /// Insert only the IPv4 header checksum (CIC = 0b01).
+    IpHeader,
+    /// Insert the IPv4 header and payload (TCP/UDP/ICMP) checksums, but
+    /// assume the pseudo-header checksum is already present (CIC = 0b10).
+    IpHeaderAndPayload,
+    /// Insert the full IPv4 header and payload checksums, computing the
+    /// pseudo-header too (CIC = 0b11).
+    Full,
+}
+
+impl ChecksumInsertion {
+    fn bits(self) -> u32 {
+        let cic = match self {
+            ChecksumInsertion::Disabled => 0b00,
+            ChecksumInsertion::IpHeader => 0b01,
+            ChecksumInsertion::IpHeaderAndPayload => 0b10,
+            ChecksumInsertion::Full => 0b11,
+        };
+        cic << TXDESC_0_CIC_SHIFT
+    }
+}
+
+#[repr(C)]
+struct TxDescriptor {
+    tdesc: &'static mut [RW<u32>; 4],
+}
+
+#[cfg(feature = "alloc")]
+impl Default for TxDescriptor {
+    fn default() -> Self {
+        let mut this = Self::new();
+        this.init();
+        this
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Drop for TxDescriptor {
+    fn drop(&mut self) {
+        unsafe {
+            dealloc(self.tdesc.as_mut_ptr() as *mut u8, Self::memory_layout())
+        }
+    }
+}
+
+impl TxDescriptor {
+    #[cfg(feature = "alloc")]
+    fn memory_layout() -> Layout {
+        Layout::from_size_align(4 * 4, super::ALIGNMENT)
+            .unwrap()
+    }
+
+    #[cfg(feature = "alloc")]
+    fn new() -> Self {
+        let mem = unsafe {
+            alloc(Self::memory_layout())
+        } as *mut [u32; 4];
+        assert!(!mem.is_null(), "alloc with memory_layout");
+
+        TxDescriptor {
+            tdesc: unsafe { &mut *(mem as *mut [RW<u32>; 4]) },
+        }
+    }
+
+    /// Wrap caller-provided `&'static` descriptor words, for the
+    /// heap-free [`TxRing::new_static()`](struct.TxRing.html#method.new_static)
+    /// path. No allocation happens; the storage outlives the driver.
+    fn from_static(tdesc: &'static mut [RW<u32>; 4]) -> Self {
+        let mut this = TxDescriptor { tdesc };
+        this.init();
+        this
+    }
+
+    /// Reset the descriptor words to the chained-mode default.
+    fn init(&mut self) {
+        self.write(0, TXDESC_0_TCH);
+        self.write(1, 0);
+        self.write(2, 0);
+        self.write(3, 0);
+    }
+
+    fn as_raw_ptr(&self) -> *const u8 {
+        self.tdesc.as_ptr() as *const u8
+    }
+
+    fn read(&self, i: usize) -> u32 {
+        self.tdesc[i].read()
+    }
+
+    fn write(&mut self, i: usize, data: u32) {
+        unsafe { self.tdesc[i].write(data) }
+    }
+
+    fn modify<F>(&mut self, i: usize, f: F)
+        where F: (FnOnce(u32) -> u32) {
+
+        unsafe { self.tdesc[i].modify(f) }
+    }
+
+    /// Is owned by the DMA engine?
+    pub fn is_owned(&self) -> bool {
+        (self.read(0) & TXDESC_0_OWN) == TXDESC_0_OWN
+    }
+
+    pub fn set_owned(&mut self) {
+        // Make sure the buffer-address, length and control writes above
+        // are committed before the DMA engine can observe the OWN bit
+        // and start reading the buffer. Correct only with the D-cache
+        // disabled or this descriptor region marked non-cacheable.
+        fence(Ordering::Release);
+        self.modify(0, |w| w | TXDESC_0_OWN);
+    }
+
+    pub fn has_error(&self) -> bool {
+        (self.read(0) & TXDESC_0_ES) == TXDESC_0_ES
+    }
+
+    /// Select the hardware checksum the MAC inserts for this frame,
+    /// writing the CIC field of TDES0.
+    pub fn set_checksum_insertion(&mut self, mode: ChecksumInsertion) {
+        self.modify(0, |w| (w & !TXDESC_0_CIC_MASK) | mode.bits());
+    }
+
+    pub fn set_buffer1(&mut self, buffer: *const u8, len: usize) {
+        self.write(2, buffer as u32);
+        self.modify(1, |w| {
+            (w & !TXDESC_1_TBS_MASK) |
+            ((len as u32) << TXDESC_1_TBS_SHIFT)
+        });
+    }
+
+    // points to next descriptor (TCH)
+    pub fn set_buffer2(&mut self, buffer: *const u8) {
+        self.write(3, buffer as u32);
+    }
+
+    pub fn set_end_of_ring(&mut self) {
+        self.modify(0, |w| w | TXDESC_0_TER);
+    }
+
+    /// Mark the descriptor as a single-buffer frame and hand it to the
+    /// DMA engine with the requested checksum insertion.
+    fn release(&mut self, mode: ChecksumInsertion) {
+        self.modify(0, |w| w | TXDESC_0_FS | TXDESC_0_LS | TXDESC_0_IC);
+        self.set_checksum_insertion(mode);
+        self.set_owned();
+    }
+}
+
+/// One descriptor plus the packet buffer currently in flight. Made `pub`
+/// so callers can hand a `&'static mut [TxRingEntry]` to
+/// [`TxRing::new_static()`](struct.TxRing.html#method.new_static).
+pub struct TxRingEntry {
+    desc: TxDescriptor,
+    buffer: Option<Buffer>,
+}
+
+impl TxRingEntry {
+    #[cfg(feature = "alloc")]
+    fn new() -> Self {
+        TxRingEntry {
+            desc: TxDescriptor::default(),
+            buffer: None,
+        }
+    }
+
+    /// Build an entry from caller-provided `&'static` descriptor words,
+    /// allocating nothing.
+    pub fn new_static(tdesc: &'static mut [RW<u32>; 4]) -> Self {
+        TxRingEntry {
+            desc: TxDescriptor::from_static(tdesc),
+            buffer: None,
+        }
+    }
+
+    // Used to chain all descriptors in the ring on start
+    pub fn set_next_buffer(&mut self, next: Option<&TxRingEntry>) {
+        match next {
+            Some(next_entry) => {
+                let ptr = next_entry.desc.as_raw_ptr();
+                self.desc.set_buffer2(ptr);
+            },
+            // For the last in the ring
+            None => {
+                self.desc.set_buffer2(0 as *const u8);
+                self.desc.set_end_of_ring();
+            },
+        }
+    }
+
+    /// Queue `buffer` for transmission with the given checksum insertion,
+    /// returning it again if the descriptor is still owned by the DMA
+    /// engine (the ring is full).
+    fn send(&mut self, buffer: Buffer, mode: ChecksumInsertion) -> Result<(), Buffer> {
+        if self.desc.is_owned() {
+            return Err(buffer);
+        }
+        // The previously sent buffer is done with; drop it now that the
+        // DMA engine has released the descriptor.
+        self.buffer = Some(buffer);
+        let buffer = self.buffer.as_ref().unwrap();
+        self.desc.set_buffer1(buffer.as_ptr(), buffer.len());
+        self.desc.release(mode);
+        Ok(())
+    }
+
+    /// Has a queued frame been transmitted? Errors are reported
+    /// separately by `has_error()`.
+    fn is_sent(&self) -> bool {
+        self.buffer.is_some() && ! self.desc.is_owned()
+    }
+
+    /// Still owned by the DMA engine, i.e. `send()` would have to drop
+    /// whatever buffer it's given instead of queuing it.
+    fn is_owned(&self) -> bool {
+        self.desc.is_owned()
+    }
+
+    fn has_error(&self) -> bool {
+        self.desc.has_error()
+    }
+}
+
+/// Tx DMA state
+pub struct TxRing {
+    #[cfg(feature = "alloc")]
+    entries: Vec<TxRingEntry>,
+    #[cfg(not(feature = "alloc"))]
+    entries: &'static mut [TxRingEntry],
+    next_entry: usize,
+    checksum_insertion: ChecksumInsertion,
+}
+
+impl TxRing {
+    /// Allocate
+    #[cfg(feature = "alloc")]
+    pub fn new() -> Self {
+        TxRing {
+            entries: Vec::new(),
+            next_entry: 0,
+            // Off by default so existing callers keep computing
+            // checksums in software; opt in with
+            // [`set_checksum_insertion()`](#method.set_checksum_insertion).
+            checksum_insertion: ChecksumInsertion::Disabled,
+        }
+    }
+
+    /// Build a ring over caller-provided `&'static mut` entries,
+    /// allocating nothing.
+    #[cfg(not(feature = "alloc"))]
+    pub fn new_static(entries: &'static mut [TxRingEntry]) -> Self {
+        TxRing {
+            entries,
+            next_entry: 0,
+            checksum_insertion: ChecksumInsertion::Disabled,
+        }
+    }
+
+    /// Choose which checksums the hardware inserts into outgoing frames.
+    pub fn set_checksum_insertion(&mut self, mode: ChecksumInsertion) {
+        self.checksum_insertion = mode;
+    }
+
+    /// Chain every entry's second-buffer pointer to its successor, with
+    /// the last entry closing the ring (TER).
+    fn chain(entries: &mut [TxRingEntry]) {
+        let len = entries.len();
+        for i in 0..len {
+            if i + 1 < len {
+                let (head, tail) = entries.split_at_mut(i + 1);
+                let next = tail[0].desc.as_raw_ptr();
+                head[i].desc.set_buffer2(next);
+            } else {
+                entries[i].set_next_buffer(None);
+            }
+        }
+    }
+
+    /// Setup the DMA engine (**required**)
+    pub fn start(&mut self, eth_dma: &ETHERNET_DMA) {
+        // With the heap path the ring starts empty; grow it to a single
+        // descriptor so transmission has somewhere to queue. Static
+        // storage is already sized at construction.
+        #[cfg(feature = "alloc")]
+        {
+            if self.entries.is_empty() {
+                self.entries.push(TxRingEntry::new());
+            }
+        }
+
+        Self::chain(&mut self.entries);
+
+        self.next_entry = 0;
+        let ring_ptr = self.entries[0].desc.as_raw_ptr();
+        // Register TxDescriptor
+        eth_dma.dmatdlar.write(|w| unsafe { w.stl().bits(ring_ptr as u32) });
+
+        // Start transmit
+        eth_dma.dmaomr.modify(|_, w| w.st().set_bit());
+    }
+
+    /// Demand that the DMA engine polls the current `TxDescriptor`
+    /// (when in `RunningState::Stopped`.)
+    pub fn demand_poll(&self, eth_dma: &ETHERNET_DMA) {
+        // Ensure the OWN-bit store that queued the descriptor (normal
+        // memory) is globally visible before the demand-poll write to
+        // `dmatpdr` (device memory), which otherwise may be reordered
+        // ahead and leave the engine polling a still-idle descriptor.
+        fence(Ordering::Release);
+        eth_dma.dmatpdr.write(|w| unsafe { w.tpd().bits(1) });
+    }
+
+    /// Is Tx DMA currently running?
+    pub fn is_running(&self, eth_dma: &ETHERNET_DMA) -> bool {
+        self.running_state(eth_dma).is_running()
+    }
+
+    /// Get current `RunningState`
+    pub fn running_state(&self, eth_dma: &ETHERNET_DMA) -> RunningState {
+        match eth_dma.dmasr.read().tps().bits() {
+            //  Reset or Stop Transmit Command issued
+            0b000 => RunningState::Stopped,
+            //  Fetching transmit transfer descriptor
+            0b001 => RunningState::Running,
+            //  Waiting for status
+            0b010 => RunningState::Running,
+            //  Reading Data from host memory buffer and queuing it to transmit buffer
+            0b011 => RunningState::Running,
+            //  Transmit descriptor unavailable
+            0b110 => RunningState::Stopped,
+            //  Closing transmit descriptor
+            0b111 => RunningState::Running,
+            _ => RunningState::Unknown,
+        }
+    }
+
+    /// Would the next [`send()`](#method.send) have to drop a buffer
+    /// because the descriptor it would use is still owned by the DMA
+    /// engine?
+    pub fn is_full(&self) -> bool {
+        self.entries[self.next_entry].is_owned()
+    }
+
+    /// Queue `buffer` for transmission with the ring's current checksum
+    /// insertion mode.
+    pub fn send(&mut self, buffer: Buffer) {
+        let mode = self.checksum_insertion;
+        // Drop the result: on a full ring `buffer` itself is silently
+        // dropped and the in-flight frame is left alone, matching the
+        // existing polling `send()` contract that takes ownership
+        // unconditionally.
+        let _ = self.entries[self.next_entry].send(buffer, mode);
+        self.next_entry += 1;
+        if self.next_entry >= self.entries.len() {
+            self.next_entry = 0;
+        }
+    }
+
+    /// Amount of unsent packets still owned by the DMA engine.
+    pub fn queue_len(&self) -> usize {
+        self.entries.iter()
+            .filter(|entry| entry.buffer.is_some() && ! entry.is_sent())
+            .count()
+    }
+
+    /// Did any transmitted descriptor report an error?
+    pub fn has_error(&self) -> bool {
+        self.entries.iter().any(|entry| entry.is_sent() && entry.has_error())
+    }
+}
+
+/// One DMA descriptor's worth of transmit storage, aligned so the engine
+/// never drops the low address bits (see
+/// [`ALIGNMENT`](../constant.ALIGNMENT.html)).
+#[repr(C, align(8))]
+#[derive(Clone, Copy)]
+pub struct TxDescriptorBlock([u32; 4]);
+
+/// Const-constructible, heap-free descriptor storage for a ring of `N`
+/// transmit entries, modeled on the embassy `PacketQueue`.
+#[repr(C, align(8))]
+pub struct TxQueue<const N: usize> {
+    descriptors: [TxDescriptorBlock; N],
+    entries: [mem::MaybeUninit<TxRingEntry>; N],
+}
+
+impl<const N: usize> TxQueue<N> {
+    /// Create zeroed storage. The entries are filled in by
+    /// [`tx_ring()`](#method.tx_ring).
+    pub const fn new() -> Self {
+        TxQueue {
+            descriptors: [TxDescriptorBlock([0; 4]); N],
+            // SAFETY: an array of `MaybeUninit` needs no initialization.
+            entries: unsafe { mem::MaybeUninit::uninit().assume_init() },
+        }
+    }
+
+    /// Build a [`TxRing`](struct.TxRing.html) over this storage.
+    #[cfg(not(feature = "alloc"))]
+    pub fn tx_ring(&'static mut self) -> TxRing {
+        let descriptors = self.descriptors.as_mut_ptr();
+        for i in 0..N {
+            // SAFETY: each index is touched once, so the `&'static mut`
+            // references handed to the entries are disjoint and live as
+            // long as `self`.
+            let tdesc = unsafe { &mut *(descriptors.add(i) as *mut [RW<u32>; 4]) };
+            self.entries[i] = mem::MaybeUninit::new(TxRingEntry::new_static(tdesc));
+        }
+        let entries = unsafe {
+            &mut *(self.entries.as_mut_ptr() as *mut [TxRingEntry; N])
+        };
+        TxRing::new_static(&mut entries[..])
+    }
+}
+
+/// Running state of the `TxRing`
+#[derive(PartialEq, Eq, Debug)]
+pub enum RunningState {
+    Unknown,
+    Stopped,
+    Running,
+}
+
+impl RunningState {
+    /// whether self equals to `RunningState::Running`
+    pub fn is_running(&self) -> bool {
+        *self == RunningState::Running
+    }
+}